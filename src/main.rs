@@ -1,15 +1,53 @@
 mod api;
 mod quote;
+mod server;
 mod utils;
 
+use std::str::FromStr;
+use std::sync::Arc;
+
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv::dotenv()?;
     env_logger::init();
 
-    let (quote, slippage_adjusted_quote) = quote::get_quote().await?;
+    let server_mode = std::env::var("SERVER_MODE")
+        .map(|v| v.parse::<bool>().unwrap_or(false))
+        .unwrap_or(false);
+    if server_mode {
+        return run_server().await;
+    }
+
+    let stream_mode = std::env::var("STREAM_MODE")
+        .map(|v| v.parse::<bool>().unwrap_or(false))
+        .unwrap_or(false);
+    if stream_mode {
+        return quote::run_stream().await;
+    }
+
+    let (quote, slippage_adjusted_quote, fully_routed) = quote::get_quote().await?;
     println!("Quote: {}", quote);
     println!("Slippage_adjusted_quote: {}", slippage_adjusted_quote);
+    if !fully_routed {
+        println!("Warning: amount exceeds available liquidity within the hop limit");
+    }
+
+    Ok(())
+}
+
+/// Starts the `quote` JSON-RPC server instead of producing a single quote and exiting, so
+/// integrators can request arbitrary quotes at runtime without respawning the process.
+async fn run_server() -> anyhow::Result<()> {
+    let http_url = std::env::var("HTTP_URL")?;
+    let whirlpool_program = Pubkey::from_str(&std::env::var("WHIRLPOOL_PROGRAM_ID")?)?;
+    let override_cache = std::env::var("OVERRIDE_CACHE")?.parse::<bool>()?;
+    let addr = std::env::var("RPC_ADDR").unwrap_or_else(|_| "127.0.0.1:9944".to_string());
 
+    let client = Arc::new(RpcClient::new(http_url));
+    let handle = server::run_server(addr.parse()?, client, whirlpool_program, override_cache).await?;
+    handle.stopped().await;
     Ok(())
 }
@@ -2,25 +2,50 @@ use crate::api;
 use crate::utils::deserialize_anchor_account;
 
 use std::cell::RefCell;
-use std::collections::VecDeque;
-use std::ops::{Add, Div, Mul};
+use std::collections::{HashMap, VecDeque};
 use std::rc::Rc;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
+use futures_util::{SinkExt, StreamExt};
 use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::pubkey::Pubkey;
+use tokio::sync::mpsc;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
 
+use whirlpool::errors::ErrorCode as WhirlpoolErrorCode;
 use whirlpool::manager::swap_manager::swap;
-use whirlpool::math::tick_math::{MAX_SQRT_PRICE_X64, MIN_SQRT_PRICE_X64};
+use whirlpool::math::tick_math::{
+    sqrt_price_from_tick_index, MAX_SQRT_PRICE_X64, MIN_SQRT_PRICE_X64,
+};
 use whirlpool::state::{
     tick::{MAX_TICK_INDEX, MIN_TICK_INDEX, TICK_ARRAY_SIZE},
     TickArray, Whirlpool,
 };
 use whirlpool::util::SwapTickSequence;
 
+/// An exact numerator/denominator percentage, used in place of `f64` slippage so adjustments
+/// to `u64` token amounts are lossless and deterministic across platforms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Percentage {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl Percentage {
+    pub fn new(numerator: u64, denominator: u64) -> Self {
+        Self {
+            numerator,
+            denominator,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct WhirlpoolArbState {
     pub override_cache: bool,
@@ -29,7 +54,10 @@ pub struct WhirlpoolArbState {
     pub in_token: Pubkey,
     pub out_token: Pubkey,
     pub whirlpool_program: Pubkey,
-    pub slippage: f64,
+    pub slippage: Percentage,
+    pub amount_specified_is_input: bool,
+    pub max_tick_array_hops: u16,
+    pub sqrt_price_limit: Option<u128>,
 }
 
 fn get_environment_variables() -> Result<WhirlpoolArbState> {
@@ -39,7 +67,26 @@ fn get_environment_variables() -> Result<WhirlpoolArbState> {
     let in_token = Pubkey::from_str(&std::env::var("INPUT_TOKEN")?)?;
     let out_token = Pubkey::from_str(&std::env::var("OUTPUT_TOKEN")?)?;
     let whirlpool_program = Pubkey::from_str(&std::env::var("WHIRLPOOL_PROGRAM_ID")?)?;
-    let slippage = std::env::var("SLIPPAGE")?.parse::<f64>()?;
+    let slippage_numerator = std::env::var("SLIPPAGE_NUMERATOR")?.parse::<u64>()?;
+    let slippage_denominator = match std::env::var("SLIPPAGE_DENOMINATOR") {
+        Ok(v) => v.parse::<u64>()?,
+        Err(_) => 100,
+    };
+    let slippage = Percentage::new(slippage_numerator, slippage_denominator);
+    // Defaults to `true` (exact-in) so existing deployments that don't set this stay on the
+    // prior behavior.
+    let amount_specified_is_input = match std::env::var("AMOUNT_SPECIFIED_IS_INPUT") {
+        Ok(v) => v.parse::<bool>()?,
+        Err(_) => true,
+    };
+    let max_tick_array_hops = match std::env::var("MAX_TICK_ARRAY_HOPS") {
+        Ok(v) => v.parse::<u16>()?,
+        Err(_) => DEFAULT_MAX_TICK_ARRAY_HOPS,
+    };
+    let sqrt_price_limit = match std::env::var("SQRT_PRICE_LIMIT") {
+        Ok(v) => Some(v.parse::<u128>()?),
+        Err(_) => None,
+    };
     Ok(WhirlpoolArbState {
         override_cache,
         http_url,
@@ -48,17 +95,33 @@ fn get_environment_variables() -> Result<WhirlpoolArbState> {
         out_token,
         whirlpool_program,
         slippage,
+        amount_specified_is_input,
+        max_tick_array_hops,
+        sqrt_price_limit,
     })
 }
 
-/// Returns `(quote, slippage_adjusted_quote)`
-pub async fn get_quote() -> anyhow::Result<(u64, u64)> {
+/// The result of routing a swap to the single best pool for a pair.
+#[derive(Clone, Debug)]
+pub struct BestQuote {
+    pub pool_address: Pubkey,
+    pub quote: u64,
+    pub slippage_adjusted_quote: u64,
+    pub fee_rate: f64,
+    pub fully_routed: bool,
+}
+
+/// Returns `(quote, slippage_adjusted_quote, fully_routed)`. `fully_routed` is `false` when
+/// the full amount could not be swapped: `max_tick_array_hops` was exhausted, the price limit
+/// was reached, or no further initialized tick array was found.
+pub async fn get_quote() -> anyhow::Result<(u64, u64, bool)> {
     let arb_state: WhirlpoolArbState = get_environment_variables().unwrap();
     let pools = api::get_whirlpools(arb_state.override_cache).await?;
 
     let in_token = arb_state.in_token;
     let out_token = arb_state.out_token;
     let amount = arb_state.amount;
+    let amount_specified_is_input = arb_state.amount_specified_is_input;
 
     info!("Initiating swap. Input={}. Output={}", in_token, out_token);
 
@@ -75,7 +138,6 @@ pub async fn get_quote() -> anyhow::Result<(u64, u64)> {
         pool_info.token_a.mint, pool_info.token_b.mint, pool_info.tick_spacing
     );
     let a_to_b = pool_info.token_a.mint == in_token && pool_info.token_b.mint == out_token;
-    let amount_specified_is_input = true;
 
     let client = Arc::new(RpcClient::new(arb_state.http_url));
     let whirlpool_account = client.get_account(&pool_info.address).await?;
@@ -88,55 +150,679 @@ pub async fn get_quote() -> anyhow::Result<(u64, u64)> {
         &arb_state.whirlpool_program,
         &pool_info.address,
     )
-    .await?;
-    let mut tick_arrays = tick_arrays
-        .into_iter()
-        .map(|a| Rc::new(RefCell::new(a)))
-        .collect::<VecDeque<_>>();
+    .await?
+    .ok_or_else(|| anyhow!("tick array uninitialized around pool's current price"))?;
 
-    let tick_array_0 = tick_arrays.pop_front().unwrap();
-    let tick_array_1 = tick_arrays.pop_front().unwrap();
-    let tick_array_2 = tick_arrays.pop_front().unwrap();
+    let (quote, slippage_adjusted_quote, fully_routed, _realized_fee_rate) = quote_single_pool(
+        &client,
+        &arb_state.whirlpool_program,
+        &pool_info.address,
+        whirlpool,
+        tick_arrays,
+        amount,
+        arb_state.slippage,
+        amount_specified_is_input,
+        a_to_b,
+        arb_state.max_tick_array_hops,
+        arb_state.sqrt_price_limit,
+    )
+    .await?;
+    if !fully_routed {
+        warn!(
+            "Quote for pool {} was not fully routed within {} tick-array hops; amount exceeds available liquidity",
+            pool_info.address, arb_state.max_tick_array_hops
+        );
+    }
+    Ok((quote, slippage_adjusted_quote, fully_routed))
+}
 
-    let mut swap_tick_sequence = SwapTickSequence::new(
-        tick_array_0.try_borrow_mut().ok().expect("not borrowed"),
-        tick_array_1.try_borrow_mut().ok(),
-        tick_array_2.try_borrow_mut().ok(),
+/// Routes a swap to the pool that gives the best price across every fee tier listed for a
+/// pair, instead of settling for the first `WhirlPoolList` match.
+///
+/// Collects every pool matching `(in_token, out_token)` and batches both their `Whirlpool`
+/// account fetches and their tick-array fetches into one `get_multiple_accounts` call each
+/// (instead of one additional round trip per pool), quotes each one with
+/// [`quote_single_pool`], and returns the pool address and quote that maximizes output (or
+/// minimizes input, for exact-out) along with the fee rate actually realized by that quote.
+/// `pools` is taken by reference so callers serving many requests can load the `WhirlPoolList`
+/// once and reuse it, rather than re-fetching (and potentially re-hitting the Orca API) on
+/// every call.
+pub async fn get_best_quote(
+    client: Arc<RpcClient>,
+    whirlpool_program: Pubkey,
+    in_token: Pubkey,
+    out_token: Pubkey,
+    amount: u64,
+    slippage: Percentage,
+    amount_specified_is_input: bool,
+    pools: &api::WhirlPoolList,
+    max_tick_array_hops: u16,
+    sqrt_price_limit: Option<u128>,
+) -> Result<BestQuote> {
+    let candidates: Vec<&api::WhirlPool> = pools
+        .whirlpools
+        .iter()
+        .filter(|pool| {
+            pool.token_a.mint == in_token && pool.token_b.mint == out_token
+                || pool.token_a.mint == out_token && pool.token_b.mint == in_token
+        })
+        .collect();
+    if candidates.is_empty() {
+        return Err(anyhow!("No pools found for pair"));
+    }
+    info!(
+        "Found {} candidate pools for swap. Input={}. Output={}",
+        candidates.len(),
+        in_token,
+        out_token
     );
 
-    let sqrt_price_limit = get_default_sqrt_price_limit(a_to_b);
+    let whirlpool_addresses: Vec<Pubkey> = candidates.iter().map(|pool| pool.address).collect();
+    let whirlpool_accounts = client.get_multiple_accounts(&whirlpool_addresses).await?;
+
+    // Deserialize every candidate's `Whirlpool` up front so its `tick_current_index` is known,
+    // then batch all candidates' tick-array keys into a single `get_multiple_accounts` call
+    // rather than fetching each pool's window in its own round trip.
+    let mut pool_whirlpools: Vec<Option<(bool, Whirlpool)>> =
+        Vec::with_capacity(candidates.len());
+    let mut tick_array_keys: Vec<Pubkey> = Vec::with_capacity(candidates.len() * 3);
+    for (pool_info, account) in candidates.iter().zip(whirlpool_accounts) {
+        let Some(account) = account else {
+            warn!("No account data for pool {}, skipping", pool_info.address);
+            pool_whirlpools.push(None);
+            continue;
+        };
+        let a_to_b = pool_info.token_a.mint == in_token && pool_info.token_b.mint == out_token;
+        let whirlpool = match deserialize_anchor_account::<Whirlpool>(&account) {
+            Ok(whirlpool) => whirlpool,
+            Err(e) => {
+                warn!("Failed to deserialize pool {}: {}", pool_info.address, e);
+                pool_whirlpools.push(None);
+                continue;
+            }
+        };
+        let keys = get_tick_array_keys(
+            whirlpool.tick_current_index,
+            whirlpool.tick_spacing as i32,
+            a_to_b,
+            &whirlpool_program,
+            &pool_info.address,
+        );
+        if keys.len() != MAX_SWAP_TICK_ARRAYS as usize {
+            warn!("Incomplete tick-array window for pool {}, skipping", pool_info.address);
+            pool_whirlpools.push(None);
+            continue;
+        }
+        tick_array_keys.extend(keys);
+        pool_whirlpools.push(Some((a_to_b, whirlpool)));
+    }
+    let tick_array_accounts = client.get_multiple_accounts(&tick_array_keys).await?;
+    let mut tick_array_accounts = tick_array_accounts.into_iter();
+
+    let mut best: Option<BestQuote> = None;
+    for (pool_info, pool_whirlpool) in candidates.iter().zip(pool_whirlpools) {
+        let Some((a_to_b, whirlpool)) = pool_whirlpool else {
+            continue;
+        };
+        // Every candidate contributed exactly `MAX_SWAP_TICK_ARRAYS` keys above, in order, so
+        // pulling that many off the front of the shared response realigns them with this pool.
+        let accounts = (&mut tick_array_accounts)
+            .take(MAX_SWAP_TICK_ARRAYS as usize)
+            .collect::<Vec<_>>();
+        let mut tick_arrays = Vec::with_capacity(accounts.len());
+        let mut window_complete = true;
+        for account in accounts {
+            match account {
+                Some(account) => match deserialize_anchor_account::<TickArray>(&account) {
+                    Ok(tick_array) => tick_arrays.push(tick_array),
+                    Err(e) => {
+                        warn!("Failed to deserialize tick array for pool {}: {}", pool_info.address, e);
+                        window_complete = false;
+                        break;
+                    }
+                },
+                None => {
+                    warn!(
+                        "Tick array uninitialized around current price for pool {}, skipping",
+                        pool_info.address
+                    );
+                    window_complete = false;
+                    break;
+                }
+            }
+        }
+        if !window_complete {
+            continue;
+        }
+
+        let (quote, slippage_adjusted_quote, fully_routed, realized_fee_rate) =
+            match quote_single_pool(
+                &client,
+                &whirlpool_program,
+                &pool_info.address,
+                whirlpool,
+                tick_arrays,
+                amount,
+                slippage,
+                amount_specified_is_input,
+                a_to_b,
+                max_tick_array_hops,
+                sqrt_price_limit,
+            )
+            .await
+            {
+                Ok(quote) => quote,
+                Err(e) => {
+                    warn!("Failed to quote pool {}: {}", pool_info.address, e);
+                    continue;
+                }
+            };
+        if !fully_routed {
+            warn!(
+                "Quote for pool {} was not fully routed within {} tick-array hops",
+                pool_info.address, max_tick_array_hops
+            );
+        }
+
+        // A partially-routed quote moved less of the order, so it isn't comparable to a fully
+        // routed one on `quote` alone (e.g. in exact-out mode it looks like a smaller input for
+        // delivering less output). Prefer any fully routed candidate over a partial one first.
+        let is_better = match &best {
+            None => true,
+            Some(current) if fully_routed != current.fully_routed => fully_routed,
+            Some(current) if amount_specified_is_input => quote > current.quote,
+            Some(current) => quote < current.quote,
+        };
+        if is_better {
+            best = Some(BestQuote {
+                pool_address: pool_info.address,
+                quote,
+                slippage_adjusted_quote,
+                fee_rate: realized_fee_rate,
+                fully_routed,
+            });
+        }
+    }
+
+    best.ok_or_else(|| anyhow!("No routable pool found for pair"))
+}
+
+/// Default cap on how many additional tick-array windows a quote will traverse beyond the
+/// initial three, bounding RPC round trips for amounts that drain all liquidity in range.
+pub(crate) const DEFAULT_MAX_TICK_ARRAY_HOPS: u16 = 4;
+
+/// Runs a swap against a pool, hopping into further tick-array windows while the amount isn't
+/// fully filled, up to `max_tick_array_hops` additional fetches beyond the initial window.
+/// Returns `(quote, slippage_adjusted_quote, fully_routed, realized_fee_rate)`, where
+/// `fully_routed` is `false` when the full amount could not be swapped (the hop budget ran
+/// out, the price limit was reached, or no further initialized tick array was found), and
+/// `realized_fee_rate` is the fees actually paid as a fraction of the total input
+/// swapped (as opposed to the pool's nominal, listed `lp_fee_rate`). Shared by [`get_quote`]
+/// and [`get_best_quote`] so routing across several pools only duplicates the swap math, not
+/// the account-fetching.
+async fn quote_single_pool(
+    client: &RpcClient,
+    whirlpool_program: &Pubkey,
+    pool_address: &Pubkey,
+    mut whirlpool: Whirlpool,
+    mut tick_arrays: Vec<TickArray>,
+    amount: u64,
+    slippage: Percentage,
+    amount_specified_is_input: bool,
+    a_to_b: bool,
+    max_tick_array_hops: u16,
+    sqrt_price_limit: Option<u128>,
+) -> Result<(u64, u64, bool, f64)> {
+    let overall_sqrt_price_limit =
+        sqrt_price_limit.unwrap_or_else(|| get_default_sqrt_price_limit(a_to_b));
     let timestamp = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)?
         .as_secs();
-    let swap_result = swap(
-        &whirlpool,
-        &mut swap_tick_sequence,
-        amount,
-        sqrt_price_limit,
+
+    let mut remaining_amount = amount;
+    let mut total_amount_in: u64 = 0;
+    let mut total_amount_out: u64 = 0;
+    let mut total_fee_amount: u64 = 0;
+    let mut fully_routed = false;
+
+    for hop in 0..=max_tick_array_hops {
+        // Bound this hop's swap to the edge of the window we actually fetched. With the
+        // default (wide-open) limit, `swap` errors with `TickArraySequenceInvalidIndex`
+        // rather than returning a partial fill once the price would need to move into a 4th,
+        // not-yet-fetched array, so we must never ask it to go further than what's loaded.
+        let hop_sqrt_price_limit = bound_sqrt_price_limit_to_window(
+            &tick_arrays,
+            whirlpool.tick_spacing as i32,
+            a_to_b,
+            overall_sqrt_price_limit,
+        );
+
+        let mut tick_array_queue = tick_arrays
+            .into_iter()
+            .map(|a| Rc::new(RefCell::new(a)))
+            .collect::<VecDeque<_>>();
+        let tick_array_0 = tick_array_queue.pop_front().unwrap();
+        let tick_array_1 = tick_array_queue.pop_front().unwrap();
+        let tick_array_2 = tick_array_queue.pop_front().unwrap();
+
+        let mut swap_tick_sequence = SwapTickSequence::new(
+            tick_array_0.try_borrow_mut().ok().expect("not borrowed"),
+            tick_array_1.try_borrow_mut().ok(),
+            tick_array_2.try_borrow_mut().ok(),
+        );
+
+        let swap_result = match swap(
+            &whirlpool,
+            &mut swap_tick_sequence,
+            remaining_amount,
+            hop_sqrt_price_limit,
+            amount_specified_is_input,
+            a_to_b,
+            timestamp,
+        ) {
+            Ok(swap_result) => swap_result,
+            // Defensive fallback: shouldn't be reachable now that the swap is always bounded
+            // to the loaded window, but still safer than propagating a raw abort mid-route.
+            Err(WhirlpoolErrorCode::TickArraySequenceInvalidIndex) => {
+                info!(
+                    "Tick-array window exhausted at hop {} before filling the remaining amount",
+                    hop
+                );
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        info!("Swap update (hop {}): {:#?}", hop, swap_result);
+
+        let (_, amount_in, amount_out) =
+            resolve_quote_amounts(swap_result.amount_a, swap_result.amount_b, a_to_b);
+        total_amount_in += amount_in;
+        total_amount_out += amount_out;
+        total_fee_amount += swap_result.fee_amount;
+
+        let filled = if amount_specified_is_input {
+            amount_in
+        } else {
+            amount_out
+        };
+        if filled >= remaining_amount {
+            fully_routed = true;
+            break;
+        }
+        remaining_amount -= filled;
+
+        // The swap stopped short of the remaining amount because it hit the caller's own
+        // price limit, not because the window ran out — hopping further would route past a
+        // bound the caller explicitly asked us to respect.
+        let hit_overall_limit = if a_to_b {
+            swap_result.next_sqrt_price <= overall_sqrt_price_limit
+        } else {
+            swap_result.next_sqrt_price >= overall_sqrt_price_limit
+        };
+        if hit_overall_limit || hop == max_tick_array_hops {
+            break;
+        }
+
+        whirlpool.tick_current_index = swap_result.next_tick_current_index;
+        whirlpool.sqrt_price = swap_result.next_sqrt_price;
+        whirlpool.liquidity = swap_result.next_liquidity;
+
+        info!(
+            "Amount not fully routed after hop {}, fetching next tick-array window at tick {}",
+            hop, whirlpool.tick_current_index
+        );
+        tick_arrays = match get_tick_arrays(
+            client,
+            whirlpool.tick_current_index,
+            whirlpool.tick_spacing as i32,
+            a_to_b,
+            whirlpool_program,
+            pool_address,
+        )
+        .await?
+        {
+            Some(tick_arrays) => tick_arrays,
+            None => {
+                info!(
+                    "Tick array uninitialized at tick {}, no more liquidity to hop into",
+                    whirlpool.tick_current_index
+                );
+                break;
+            }
+        };
+    }
+
+    let quote = if amount_specified_is_input {
+        total_amount_out
+    } else {
+        total_amount_in
+    };
+    let slippage_adjusted_quote = calculate_swap_amounts_from_quote(
+        total_amount_in,
+        total_amount_out,
+        slippage,
         amount_specified_is_input,
-        a_to_b,
-        timestamp,
     )?;
-
-    info!("Swap update: {:#?}", swap_result);
-    let quote = if a_to_b {
-        swap_result.amount_b
+    let realized_fee_rate = if total_amount_in > 0 {
+        total_fee_amount as f64 / total_amount_in as f64
     } else {
-        swap_result.amount_a
+        0.0
     };
-    let (amount_in, amount_out) = if a_to_b == amount_specified_is_input {
-        (swap_result.amount_a, swap_result.amount_b)
+    Ok((quote, slippage_adjusted_quote, fully_routed, realized_fee_rate))
+}
+
+/// Picks out the output amount (the headline quote of an exact-in swap) and the
+/// `(amount_in, amount_out)` pair from a swap result.
+/// `amount_a`/`amount_b` are the real token-A/token-B deltas `swap` moved, independent of
+/// whether `amount` was specified as the input or the desired output, so only the swap
+/// direction (`a_to_b`) determines which side is in and which is out.
+fn resolve_quote_amounts(amount_a: u64, amount_b: u64, a_to_b: bool) -> (u64, u64, u64) {
+    let quote = if a_to_b { amount_b } else { amount_a };
+    let (amount_in, amount_out) = if a_to_b {
+        (amount_a, amount_b)
     } else {
-        (swap_result.amount_b, swap_result.amount_a)
+        (amount_b, amount_a)
     };
+    (quote, amount_in, amount_out)
+}
 
-    let slippage_adjusted_quote = calculate_swap_amounts_from_quote(
-        amount_in,
-        amount_out,
+/// Re-quotes a pool on every account update instead of fetching a single snapshot.
+///
+/// Opens a websocket connection to `ws_url` and issues an `accountSubscribe` for the
+/// `Whirlpool` account plus its current `TickArray` PDAs. Each notification re-deserializes
+/// the changed account, rebuilds the `SwapTickSequence` and re-runs `swap`, pushing the
+/// refreshed `(quote, slippage_adjusted_quote)` pair onto the returned channel. The socket
+/// is reconnected with exponential backoff on error, and the tick-array subscriptions are
+/// recomputed whenever `tick_current_index` crosses into a different array.
+pub async fn stream_quotes(
+    ws_url: String,
+    client: Arc<RpcClient>,
+    whirlpool_program: Pubkey,
+    pool_info: api::WhirlPool,
+    in_token: Pubkey,
+    out_token: Pubkey,
+    amount: u64,
+    slippage: Percentage,
+    amount_specified_is_input: bool,
+    max_tick_array_hops: u16,
+    sqrt_price_limit: Option<u128>,
+) -> Result<mpsc::Receiver<(u64, u64)>> {
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        let mut backoff = Duration::from_secs(1);
+        loop {
+            let connected_at = std::time::Instant::now();
+            match run_quote_subscription(
+                &ws_url,
+                &client,
+                &whirlpool_program,
+                &pool_info,
+                in_token,
+                out_token,
+                amount,
+                slippage,
+                amount_specified_is_input,
+                max_tick_array_hops,
+                sqrt_price_limit,
+                &tx,
+            )
+            .await
+            {
+                Ok(()) => {
+                    info!("stream_quotes receiver dropped, closing subscription");
+                    break;
+                }
+                Err(e) => {
+                    // Measured before sleeping, so the backoff wait itself doesn't count as
+                    // time the connection stayed up.
+                    let stayed_up = connected_at.elapsed() >= backoff;
+                    warn!(
+                        "stream_quotes subscription error: {}. Reconnecting in {:?}",
+                        e, backoff
+                    );
+                    tokio::time::sleep(backoff).await;
+                    // A connection that stayed up at least as long as the last backoff proved
+                    // itself stable, so don't let a single blip ratchet the delay to the cap
+                    // forever; only grow backoff when reconnects are happening back-to-back.
+                    if stayed_up {
+                        backoff = Duration::from_secs(1);
+                    } else {
+                        backoff = (backoff * 2).min(Duration::from_secs(30));
+                    }
+                }
+            }
+        }
+    });
+    Ok(rx)
+}
+
+/// Runs [`stream_quotes`] for the pair configured via environment variables and logs each
+/// refreshed quote as it arrives, for long-running processes that want live prices instead of
+/// a one-shot CLI quote.
+pub async fn run_stream() -> anyhow::Result<()> {
+    let arb_state = get_environment_variables()?;
+    let ws_url = std::env::var("WS_URL")?;
+    let pools = api::get_whirlpools(arb_state.override_cache).await?;
+
+    let pool_info = pools
+        .whirlpools
+        .into_iter()
+        .find(|pool| {
+            pool.token_a.mint == arb_state.in_token && pool.token_b.mint == arb_state.out_token
+                || pool.token_a.mint == arb_state.out_token
+                    && pool.token_b.mint == arb_state.in_token
+        })
+        .ok_or_else(|| anyhow!("Failed to get pool information for pair"))?;
+    info!(
+        "Streaming quotes. Mint0={}. Mint1={}. Tick-spacing={}",
+        pool_info.token_a.mint, pool_info.token_b.mint, pool_info.tick_spacing
+    );
+
+    let client = Arc::new(RpcClient::new(arb_state.http_url));
+    let mut quotes = stream_quotes(
+        ws_url,
+        client,
+        arb_state.whirlpool_program,
+        pool_info,
+        arb_state.in_token,
+        arb_state.out_token,
+        arb_state.amount,
         arb_state.slippage,
-        amount_specified_is_input,
+        arb_state.amount_specified_is_input,
+        arb_state.max_tick_array_hops,
+        arb_state.sqrt_price_limit,
+    )
+    .await?;
+
+    while let Some((quote, slippage_adjusted_quote)) = quotes.recv().await {
+        println!("Quote: {}", quote);
+        println!("Slippage_adjusted_quote: {}", slippage_adjusted_quote);
+    }
+    Ok(())
+}
+
+/// Drives a single websocket connection until it errors or the receiver is dropped.
+async fn run_quote_subscription(
+    ws_url: &str,
+    client: &RpcClient,
+    whirlpool_program: &Pubkey,
+    pool_info: &api::WhirlPool,
+    in_token: Pubkey,
+    out_token: Pubkey,
+    amount: u64,
+    slippage: Percentage,
+    amount_specified_is_input: bool,
+    max_tick_array_hops: u16,
+    sqrt_price_limit: Option<u128>,
+    tx: &mpsc::Sender<(u64, u64)>,
+) -> Result<()> {
+    let a_to_b = pool_info.token_a.mint == in_token && pool_info.token_b.mint == out_token;
+
+    let (ws_stream, _) = connect_async(ws_url).await?;
+    let (mut write, mut read) = ws_stream.split();
+
+    let whirlpool_account = client.get_account(&pool_info.address).await?;
+    let mut whirlpool = deserialize_anchor_account::<Whirlpool>(&whirlpool_account)?;
+    let mut tick_array_keys = get_tick_array_keys(
+        whirlpool.tick_current_index,
+        whirlpool.tick_spacing as i32,
+        a_to_b,
+        whirlpool_program,
+        &pool_info.address,
     );
-    Ok((quote, slippage_adjusted_quote))
+
+    let mut next_request_id = 1u64;
+    let mut pending_subs: HashMap<u64, Pubkey> = HashMap::new();
+    let mut sub_to_account: HashMap<u64, Pubkey> = HashMap::new();
+    let mut account_to_sub: HashMap<Pubkey, u64> = HashMap::new();
+
+    subscribe_account(
+        &mut write,
+        &mut next_request_id,
+        &mut pending_subs,
+        pool_info.address,
+    )
+    .await?;
+    for key in &tick_array_keys {
+        subscribe_account(&mut write, &mut next_request_id, &mut pending_subs, *key).await?;
+    }
+
+    loop {
+        let message = match read.next().await {
+            Some(message) => message?,
+            None => return Err(anyhow!("websocket stream ended")),
+        };
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Ping(payload) => {
+                write.send(Message::Pong(payload)).await?;
+                continue;
+            }
+            Message::Close(_) => return Err(anyhow!("websocket closed by server")),
+            _ => continue,
+        };
+        let value: Value = serde_json::from_str(&text)?;
+
+        // Subscription confirmations carry `{"id": <request id>, "result": <subscription id>}`.
+        if let (Some(request_id), Some(subscription_id)) = (
+            value.get("id").and_then(Value::as_u64),
+            value.get("result").and_then(Value::as_u64),
+        ) {
+            if let Some(account) = pending_subs.remove(&request_id) {
+                sub_to_account.insert(subscription_id, account);
+                account_to_sub.insert(account, subscription_id);
+            }
+            continue;
+        }
+
+        let Some(subscription_id) = value.pointer("/params/subscription").and_then(Value::as_u64)
+        else {
+            continue;
+        };
+        let Some(&changed_account) = sub_to_account.get(&subscription_id) else {
+            continue;
+        };
+
+        info!("Account update for {}, re-quoting", changed_account);
+        if changed_account == pool_info.address {
+            let whirlpool_account = client.get_account(&pool_info.address).await?;
+            whirlpool = deserialize_anchor_account::<Whirlpool>(&whirlpool_account)?;
+        }
+
+        let new_tick_array_keys = get_tick_array_keys(
+            whirlpool.tick_current_index,
+            whirlpool.tick_spacing as i32,
+            a_to_b,
+            whirlpool_program,
+            &pool_info.address,
+        );
+        if new_tick_array_keys != tick_array_keys {
+            info!("tick_current_index crossed an array boundary, resubscribing to tick arrays");
+            for key in &tick_array_keys {
+                if let Some(subscription_id) = account_to_sub.remove(key) {
+                    sub_to_account.remove(&subscription_id);
+                    unsubscribe_account(&mut write, &mut next_request_id, subscription_id).await?;
+                }
+            }
+            for key in &new_tick_array_keys {
+                subscribe_account(&mut write, &mut next_request_id, &mut pending_subs, *key)
+                    .await?;
+            }
+            tick_array_keys = new_tick_array_keys;
+        }
+
+        let tick_arrays = get_tick_arrays(
+            client,
+            whirlpool.tick_current_index,
+            whirlpool.tick_spacing as i32,
+            a_to_b,
+            whirlpool_program,
+            &pool_info.address,
+        )
+        .await?
+        .ok_or_else(|| anyhow!("tick array uninitialized around pool's current price"))?;
+
+        let (quote, slippage_adjusted_quote, fully_routed, _realized_fee_rate) = quote_single_pool(
+            client,
+            whirlpool_program,
+            &pool_info.address,
+            whirlpool.clone(),
+            tick_arrays,
+            amount,
+            slippage,
+            amount_specified_is_input,
+            a_to_b,
+            max_tick_array_hops,
+            sqrt_price_limit,
+        )
+        .await?;
+        if !fully_routed {
+            warn!(
+                "Re-quote for pool {} was not fully routed within {} tick-array hops",
+                pool_info.address, max_tick_array_hops
+            );
+        }
+
+        if tx.send((quote, slippage_adjusted_quote)).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+async fn subscribe_account(
+    write: &mut (impl SinkExt<Message, Error = tokio_tungstenite::tungstenite::Error> + Unpin),
+    next_request_id: &mut u64,
+    pending_subs: &mut HashMap<u64, Pubkey>,
+    account: Pubkey,
+) -> Result<()> {
+    let request_id = *next_request_id;
+    *next_request_id += 1;
+    pending_subs.insert(request_id, account);
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": request_id,
+        "method": "accountSubscribe",
+        "params": [account.to_string(), { "encoding": "base64", "commitment": "confirmed" }],
+    });
+    write.send(Message::Text(request.to_string())).await?;
+    Ok(())
+}
+
+async fn unsubscribe_account(
+    write: &mut (impl SinkExt<Message, Error = tokio_tungstenite::tungstenite::Error> + Unpin),
+    next_request_id: &mut u64,
+    subscription_id: u64,
+) -> Result<()> {
+    let request_id = *next_request_id;
+    *next_request_id += 1;
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": request_id,
+        "method": "accountUnsubscribe",
+        "params": [subscription_id],
+    });
+    write.send(Message::Text(request.to_string())).await?;
+    Ok(())
 }
 
 /// The maximum number of tick-arrays that can traversed across in a swap
@@ -151,6 +837,41 @@ fn get_default_sqrt_price_limit(a_to_b: bool) -> u128 {
     }
 }
 
+/// Tightens `sqrt_price_limit` to the edge of the currently loaded tick-array window, so a hop
+/// never asks `swap` to move the price past arrays it hasn't fetched. `tick_arrays` must be the
+/// same window about to be passed to `SwapTickSequence` (the last array is the far edge in the
+/// swap direction); falls back to the protocol-wide min/max tick if it's unexpectedly empty.
+fn bound_sqrt_price_limit_to_window(
+    tick_arrays: &[TickArray],
+    tick_spacing: i32,
+    a_to_b: bool,
+    sqrt_price_limit: u128,
+) -> u128 {
+    let edge_tick_index = if a_to_b {
+        tick_arrays
+            .last()
+            .map(|a| a.start_tick_index)
+            .unwrap_or(MIN_TICK_INDEX)
+    } else {
+        tick_arrays
+            .last()
+            .map(|a| a.start_tick_index + TICK_ARRAY_SIZE * tick_spacing)
+            .unwrap_or(MAX_TICK_INDEX)
+    }
+    .clamp(MIN_TICK_INDEX, MAX_TICK_INDEX);
+    let edge_sqrt_price = sqrt_price_from_tick_index(edge_tick_index);
+
+    if a_to_b {
+        sqrt_price_limit.max(edge_sqrt_price)
+    } else {
+        sqrt_price_limit.min(edge_sqrt_price)
+    }
+}
+
+/// Fetches the tick arrays covering `tick_current_index`. Returns `Ok(None)` instead of
+/// erroring when one of the PDAs isn't initialized, which is expected once a swap has pushed
+/// the price past the pools' in-range liquidity — callers that are hopping across windows
+/// treat that as the liquidity boundary rather than a fetch failure.
 async fn get_tick_arrays(
     client: &RpcClient,
     tick_current_index: i32,
@@ -158,7 +879,7 @@ async fn get_tick_arrays(
     a_to_b: bool,
     program_id: &Pubkey,
     whirlpool_address: &Pubkey,
-) -> Result<Vec<TickArray>> {
+) -> Result<Option<Vec<TickArray>>> {
     let keys = get_tick_array_keys(
         tick_current_index,
         tick_spacing,
@@ -170,12 +891,13 @@ async fn get_tick_arrays(
     let accounts = client.get_multiple_accounts(&keys).await?;
     let mut tick_arrays = Vec::with_capacity(accounts.len());
     for account in accounts {
-        let tick_array =
-            deserialize_anchor_account::<TickArray>(account.as_ref().expect("No account data"))?;
-        tick_arrays.push(tick_array);
+        let Some(account) = account else {
+            return Ok(None);
+        };
+        tick_arrays.push(deserialize_anchor_account::<TickArray>(&account)?);
     }
 
-    Ok(tick_arrays)
+    Ok(Some(tick_arrays))
 }
 
 fn get_tick_array_keys(
@@ -254,26 +976,179 @@ fn get_tick_array_address(program_id: &Pubkey, whirlpool: &Pubkey, start_tick: i
     .0
 }
 
-/// Returns the other_amount_threshhold
+/// Returns the other_amount_threshhold: a slippage-adjusted minimum output when
+/// `amount_specified_is_input` is `true`, or a slippage-adjusted maximum input when it is
+/// `false`.
 fn calculate_swap_amounts_from_quote(
     est_amount_in: u64,
     est_amount_out: u64,
-    slippage: f64,
+    slippage: Percentage,
     amount_specified_is_input: bool,
-) -> u64 {
+) -> Result<u64> {
     if amount_specified_is_input {
         adjust_for_slippage(est_amount_out, slippage, false)
     } else {
-        adjust_for_slippage(est_amount_in, slippage, false)
+        adjust_for_slippage(est_amount_in, slippage, true)
     }
 }
 
-// todo: slippage in form of percentage numerator and denominator. Currently we just
-// specify a numerator and assume a denominator of 100
-fn adjust_for_slippage(amount: u64, slippage: f64, adjust_up: bool) -> u64 {
-    if adjust_up {
-        ((amount as f64).mul(slippage.add(100.0)).div(100.0)) as u64
+/// Adjusts `amount` by `slippage` using `u128` intermediate math, avoiding the precision loss
+/// and cross-platform non-determinism of adjusting `u64` token amounts with `f64`. Errors on a
+/// zero denominator rather than dividing by it; `Percentage` is deserialized straight from
+/// untrusted RPC params, so this can't be ruled out by construction.
+fn adjust_for_slippage(amount: u64, slippage: Percentage, adjust_up: bool) -> Result<u64> {
+    if slippage.denominator == 0 {
+        return Err(anyhow!("slippage denominator must be non-zero"));
+    }
+    let amount = amount as u128;
+    let numerator = slippage.numerator as u128;
+    let denominator = slippage.denominator as u128;
+    let adjusted = if adjust_up {
+        amount * (denominator + numerator) / denominator
     } else {
-        ((amount as f64).mul(100.0).div(slippage.add(100.0))) as u64
+        amount * denominator / (denominator + numerator)
+    };
+    Ok(adjusted as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // amount_a < amount_b so a quote-selection bug (picking the wrong side) shows up as a
+    // wrong number rather than coincidentally matching.
+    const AMOUNT_A: u64 = 1_000;
+    const AMOUNT_B: u64 = 2_000;
+
+    #[test]
+    fn resolve_quote_amounts_a_to_b() {
+        let (quote, amount_in, amount_out) = resolve_quote_amounts(AMOUNT_A, AMOUNT_B, true);
+        assert_eq!(quote, AMOUNT_B);
+        assert_eq!((amount_in, amount_out), (AMOUNT_A, AMOUNT_B));
+    }
+
+    #[test]
+    fn resolve_quote_amounts_b_to_a() {
+        let (quote, amount_in, amount_out) = resolve_quote_amounts(AMOUNT_A, AMOUNT_B, false);
+        assert_eq!(quote, AMOUNT_A);
+        assert_eq!((amount_in, amount_out), (AMOUNT_B, AMOUNT_A));
+    }
+
+    #[test]
+    fn calculate_swap_amounts_exact_in_adjusts_output_down() {
+        // Exact-in: the threshold is a minimum acceptable output, so it must be adjusted
+        // downward from the estimate.
+        let slippage = Percentage::new(1, 100);
+        let threshold = calculate_swap_amounts_from_quote(AMOUNT_A, AMOUNT_B, slippage, true)
+            .expect("non-zero denominator");
+        assert!(threshold < AMOUNT_B);
+    }
+
+    #[test]
+    fn calculate_swap_amounts_exact_out_adjusts_input_up() {
+        // Exact-out: the threshold is a maximum acceptable input, so it must be adjusted
+        // upward from the estimate.
+        let slippage = Percentage::new(1, 100);
+        let threshold = calculate_swap_amounts_from_quote(AMOUNT_A, AMOUNT_B, slippage, false)
+            .expect("non-zero denominator");
+        assert!(threshold > AMOUNT_A);
+    }
+
+    #[test]
+    fn adjust_for_slippage_is_exact_u128_math() {
+        // 1/100 (1%) of 1_000_000 adjusted up should be exactly 1_010_000, with no
+        // floating-point rounding.
+        let slippage = Percentage::new(1, 100);
+        assert_eq!(adjust_for_slippage(1_000_000, slippage, true).unwrap(), 1_010_000);
+        assert_eq!(adjust_for_slippage(1_000_000, slippage, false).unwrap(), 990_099);
+    }
+
+    #[test]
+    fn adjust_for_slippage_rejects_zero_denominator() {
+        let slippage = Percentage::new(1, 0);
+        assert!(adjust_for_slippage(1_000_000, slippage, true).is_err());
+    }
+
+    // Builds a tiny, deeply-liquid fixture pool (no initialized ticks to cross) and drives a
+    // small swap through the real `swap` math via `quote_single_pool`, rather than only the
+    // pure amount-bookkeeping helpers above. A swap this small relative to `liquidity` stays
+    // within the first tick array and should fully route in a single hop for all four
+    // `(a_to_b, amount_specified_is_input)` combinations.
+    const FIXTURE_TICK_SPACING: u16 = 64;
+    const FIXTURE_TICK_CURRENT_INDEX: i32 = 0;
+    const FIXTURE_LIQUIDITY: u128 = 1_000_000_000_000_000;
+    const FIXTURE_SWAP_AMOUNT: u64 = 1_000;
+
+    fn fixture_tick_arrays(a_to_b: bool) -> Vec<TickArray> {
+        let whirlpool = Pubkey::new_unique();
+        let ticks_per_array = TICK_ARRAY_SIZE * FIXTURE_TICK_SPACING as i32;
+        let base = (FIXTURE_TICK_CURRENT_INDEX / ticks_per_array) * ticks_per_array;
+        let offsets: [i32; 3] = if a_to_b { [0, -1, -2] } else { [0, 1, 2] };
+        offsets
+            .iter()
+            .map(|offset| TickArray {
+                start_tick_index: base + offset * ticks_per_array,
+                whirlpool,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    async fn quote_fixture_pool(
+        a_to_b: bool,
+        amount_specified_is_input: bool,
+    ) -> (u64, u64, bool, f64) {
+        let whirlpool = Whirlpool {
+            tick_spacing: FIXTURE_TICK_SPACING,
+            tick_current_index: FIXTURE_TICK_CURRENT_INDEX,
+            sqrt_price: sqrt_price_from_tick_index(FIXTURE_TICK_CURRENT_INDEX),
+            liquidity: FIXTURE_LIQUIDITY,
+            ..Default::default()
+        };
+        let client = RpcClient::new("http://localhost:0".to_string());
+
+        quote_single_pool(
+            &client,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            whirlpool,
+            fixture_tick_arrays(a_to_b),
+            FIXTURE_SWAP_AMOUNT,
+            Percentage::new(1, 100),
+            amount_specified_is_input,
+            a_to_b,
+            DEFAULT_MAX_TICK_ARRAY_HOPS,
+            None,
+        )
+        .await
+        .expect("swap against fixture whirlpool should succeed")
+    }
+
+    #[tokio::test]
+    async fn quote_single_pool_a_to_b_exact_in() {
+        let (quote, _, fully_routed, _) = quote_fixture_pool(true, true).await;
+        assert!(fully_routed);
+        assert!(quote > 0);
+    }
+
+    #[tokio::test]
+    async fn quote_single_pool_a_to_b_exact_out() {
+        let (quote, _, fully_routed, _) = quote_fixture_pool(true, false).await;
+        assert!(fully_routed);
+        assert!(quote > 0);
+    }
+
+    #[tokio::test]
+    async fn quote_single_pool_b_to_a_exact_in() {
+        let (quote, _, fully_routed, _) = quote_fixture_pool(false, true).await;
+        assert!(fully_routed);
+        assert!(quote > 0);
+    }
+
+    #[tokio::test]
+    async fn quote_single_pool_b_to_a_exact_out() {
+        let (quote, _, fully_routed, _) = quote_fixture_pool(false, false).await;
+        assert!(fully_routed);
+        assert!(quote > 0);
     }
 }
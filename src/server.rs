@@ -0,0 +1,138 @@
+use crate::api::WhirlPoolList;
+use crate::quote::{self, Percentage};
+
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use jsonrpsee::core::{async_trait, RpcResult};
+use jsonrpsee::proc_macros::rpc;
+use jsonrpsee::server::{ServerBuilder, ServerHandle};
+use jsonrpsee::types::ErrorObjectOwned;
+use log::info;
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+/// Params for the `quote` RPC method.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuoteParams {
+    pub input_token: String,
+    pub output_token: String,
+    pub amount: u64,
+    pub slippage: Percentage,
+    #[serde(default = "default_amount_specified_is_input")]
+    pub amount_specified_is_input: bool,
+    #[serde(default = "default_max_tick_array_hops")]
+    pub max_tick_array_hops: u16,
+    /// Explicit price limit to cap execution against, as a string since it can exceed `u64`.
+    /// Falls back to the default min/max sqrt price for the swap direction when omitted.
+    pub sqrt_price_limit: Option<String>,
+}
+
+fn default_amount_specified_is_input() -> bool {
+    true
+}
+
+fn default_max_tick_array_hops() -> u16 {
+    quote::DEFAULT_MAX_TICK_ARRAY_HOPS
+}
+
+/// Response for the `quote` RPC method.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuoteResponse {
+    pub quote: u64,
+    pub slippage_adjusted_quote: u64,
+    pub pool_address: String,
+    pub fee_rate: f64,
+    pub fully_routed: bool,
+}
+
+#[rpc(server, namespace = "")]
+pub trait QuoteApi {
+    #[method(name = "quote")]
+    async fn quote(&self, params: QuoteParams) -> RpcResult<QuoteResponse>;
+}
+
+pub struct QuoteService {
+    client: Arc<RpcClient>,
+    whirlpool_program: Pubkey,
+    pools: WhirlPoolList,
+}
+
+impl QuoteService {
+    pub fn new(client: Arc<RpcClient>, whirlpool_program: Pubkey, pools: WhirlPoolList) -> Self {
+        Self {
+            client,
+            whirlpool_program,
+            pools,
+        }
+    }
+}
+
+#[async_trait]
+impl QuoteApiServer for QuoteService {
+    async fn quote(&self, params: QuoteParams) -> RpcResult<QuoteResponse> {
+        if params.slippage.denominator == 0 {
+            return Err(invalid_params("slippage denominator must be non-zero"));
+        }
+        let input_token = Pubkey::from_str(&params.input_token).map_err(invalid_params)?;
+        let output_token = Pubkey::from_str(&params.output_token).map_err(invalid_params)?;
+        let sqrt_price_limit = params
+            .sqrt_price_limit
+            .as_deref()
+            .map(u128::from_str)
+            .transpose()
+            .map_err(invalid_params)?;
+
+        let best = quote::get_best_quote(
+            self.client.clone(),
+            self.whirlpool_program,
+            input_token,
+            output_token,
+            params.amount,
+            params.slippage,
+            params.amount_specified_is_input,
+            &self.pools,
+            params.max_tick_array_hops,
+            sqrt_price_limit,
+        )
+        .await
+        .map_err(|e| ErrorObjectOwned::owned(1, e.to_string(), None::<()>))?;
+
+        Ok(QuoteResponse {
+            quote: best.quote,
+            slippage_adjusted_quote: best.slippage_adjusted_quote,
+            pool_address: best.pool_address.to_string(),
+            fee_rate: best.fee_rate,
+            fully_routed: best.fully_routed,
+        })
+    }
+}
+
+fn invalid_params(e: impl std::fmt::Display) -> ErrorObjectOwned {
+    ErrorObjectOwned::owned(-32602, format!("invalid params: {e}"), None::<()>)
+}
+
+/// Starts the `quote` JSON-RPC service over HTTP and WebSocket, reusing a single `RpcClient`
+/// and the cached `WhirlPoolList` across requests so integrators can request arbitrary quotes
+/// at runtime without respawning the process.
+///
+/// The pool list is loaded once, here, rather than per request: `get_whirlpools` re-reads (or,
+/// with `override_cache`, re-fetches from the Orca API) on every call, which would add latency
+/// and rate-limit risk to every `quote` RPC.
+pub async fn run_server(
+    addr: SocketAddr,
+    client: Arc<RpcClient>,
+    whirlpool_program: Pubkey,
+    override_cache: bool,
+) -> anyhow::Result<ServerHandle> {
+    let pools = crate::api::get_whirlpools(override_cache).await?;
+    let server = ServerBuilder::default().build(addr).await?;
+    let service = QuoteService::new(client, whirlpool_program, pools);
+    let handle = server.start(service.into_rpc());
+    info!("Quote RPC server listening on {}", addr);
+    Ok(handle)
+}